@@ -0,0 +1,204 @@
+//! The proc-macro backing `selfdrop`'s `#[derive(ValueDrop)]`.
+//!
+//! This crate is not meant to be depended on directly; pull it in through `selfdrop`'s
+//! `derive` feature, which re-exports the macro.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident};
+
+///Resolves the path to the `selfdrop` crate from the invoking crate's perspective: `crate` when
+/// the derive is used inside `selfdrop` itself (e.g. its own unit tests), or `::<name>` where
+/// `<name>` is whatever the invoking crate's `Cargo.toml` actually calls the dependency
+/// (handling a `package = "selfdrop"` rename) otherwise. Hardcoding `selfdrop::` here would
+/// break both of those cases.
+fn selfdrop_path() -> TokenStream2 {
+    match crate_name("selfdrop") {
+        Ok(FoundCrate::Itself) => quote! { crate },
+        Ok(FoundCrate::Name(name)) => {
+            let ident = Ident::new(&name, proc_macro2::Span::call_site());
+            quote! { ::#ident }
+        }
+        Err(_) => quote! { ::selfdrop },
+    }
+}
+
+///How a single field should be torn down by the generated `ValueDrop::drop`.
+enum FieldAction {
+    ///Call `ValueDrop::drop` on the field by value.
+    ValueDrop,
+    ///Move the field into a normal `drop(field)`, running its ordinary drop glue.
+    Plain,
+    ///`#[value_drop(skip)]`: forget the field instead of dropping it.
+    Skip,
+}
+
+///Does `ty`'s token stream mention `ident` anywhere (including nested, e.g. `Vec<T>`, `(T, U)`,
+/// `[T; 4]`)? Used to figure out which of the struct/enum's generic type parameters are actually
+/// handed to `ValueDrop::drop` by a field, so the derive only requires a `ValueDrop` bound for
+/// those - not every generic parameter the type happens to have.
+fn type_mentions_ident(ty: &syn::Type, ident: &Ident) -> bool {
+    fn walk(tokens: TokenStream2, ident: &Ident) -> bool {
+        tokens.into_iter().any(|tt| match tt {
+            proc_macro2::TokenTree::Ident(id) => id == *ident,
+            proc_macro2::TokenTree::Group(group) => walk(group.stream(), ident),
+            _ => false,
+        })
+    }
+    walk(quote! { #ty }, ident)
+}
+
+///Returns the type of every field across `fields` whose default action is to be passed to
+///`ValueDrop::drop` (i.e. not `#[value_drop(plain)]` or `#[value_drop(skip)]`).
+fn value_drop_field_types(fields: &Fields) -> Vec<&syn::Type> {
+    fields
+        .iter()
+        .filter(|field| matches!(field_action(&field.attrs), FieldAction::ValueDrop))
+        .map(|field| &field.ty)
+        .collect()
+}
+
+fn field_action(attrs: &[syn::Attribute]) -> FieldAction {
+    for attr in attrs {
+        if !attr.path().is_ident("value_drop") {
+            continue;
+        }
+        let mut action = FieldAction::ValueDrop;
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                action = FieldAction::Skip;
+            } else if meta.path.is_ident("plain") {
+                action = FieldAction::Plain;
+            } else {
+                return Err(meta.error("expected `skip` or `plain`"));
+            }
+            Ok(())
+        })
+        .expect("invalid #[value_drop(..)] attribute");
+        return action;
+    }
+    FieldAction::ValueDrop
+}
+
+///Binds every field of `fields` to a fresh identifier and returns the pattern used to
+///destructure `self` alongside the per-field teardown statements, in declaration order.
+fn bind_and_drop_fields(fields: &Fields, selfdrop: &TokenStream2) -> (TokenStream2, TokenStream2) {
+    let bindings: Vec<Ident> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| f.ident.clone().unwrap())
+            .collect(),
+        Fields::Unnamed(unnamed) => (0..unnamed.unnamed.len())
+            .map(|i| Ident::new(&format!("field_{}", i), proc_macro2::Span::call_site()))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote! { { #(#idents),* } }
+        }
+        Fields::Unnamed(_) => {
+            quote! { ( #(#bindings),* ) }
+        }
+        Fields::Unit => quote! {},
+    };
+
+    let field_attrs: Vec<&[syn::Attribute]> = match fields {
+        Fields::Named(named) => named.named.iter().map(|f| f.attrs.as_slice()).collect(),
+        Fields::Unnamed(unnamed) => unnamed.unnamed.iter().map(|f| f.attrs.as_slice()).collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    let drops = bindings.iter().zip(field_attrs.iter()).map(|(ident, attrs)| {
+        match field_action(attrs) {
+            FieldAction::ValueDrop => quote! { #selfdrop::ValueDrop::drop(#ident); },
+            FieldAction::Plain => quote! { drop(#ident); },
+            FieldAction::Skip => quote! { core::mem::forget(#ident); },
+        }
+    });
+
+    (pattern, quote! { #(#drops)* })
+}
+
+///Derives [`ValueDrop`](../selfdrop/trait.ValueDrop.html) for a struct or enum by destructuring
+///`self` and tearing down each field in top-to-bottom declaration order: fields are passed to
+///`ValueDrop::drop` by default, `#[value_drop(plain)]` fields run ordinary drop glue instead, and
+///`#[value_drop(skip)]` fields are forgotten.
+#[proc_macro_derive(ValueDrop, attributes(value_drop))]
+pub fn derive_value_drop(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident.clone();
+    let selfdrop = selfdrop_path();
+
+    let dropped_field_types: Vec<syn::Type> = match &input.data {
+        Data::Struct(data) => value_drop_field_types(&data.fields).into_iter().cloned().collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|variant| value_drop_field_types(&variant.fields))
+            .cloned()
+            .collect(),
+        Data::Union(_) => Vec::new(),
+    };
+
+    //Only require a `ValueDrop` bound for generic type parameters that a field actually passes
+    //to `ValueDrop::drop` - not every type parameter the struct/enum happens to declare.
+    let needs_bound: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .filter(|ident| dropped_field_types.iter().any(|ty| type_mentions_ident(ty, ident)))
+        .collect();
+    if !needs_bound.is_empty() {
+        let where_clause = input.generics.make_where_clause();
+        for ident in needs_bound {
+            where_clause.predicates.push(syn::parse_quote! { #ident: #selfdrop::ValueDrop });
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match input.data {
+        Data::Struct(data) => {
+            let (pattern, drops) = bind_and_drop_fields(&data.fields, &selfdrop);
+            quote! {
+                let Self #pattern = self;
+                #drops
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.into_iter().map(|variant| {
+                let variant_name = variant.ident;
+                let (pattern, drops) = bind_and_drop_fields(&variant.fields, &selfdrop);
+                quote! {
+                    Self::#variant_name #pattern => { #drops }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            panic!("#[derive(ValueDrop)] does not support unions");
+        }
+    };
+
+    let expanded = quote! {
+        impl #impl_generics #selfdrop::ValueDrop for #name #ty_generics #where_clause {
+            fn drop(self) {
+                #body
+            }
+        }
+    };
+
+    expanded.into()
+}
@@ -0,0 +1,218 @@
+//! [ValueDrop] implementations for the common compositions Rust's own drop glue handles, so by-
+//! value dropping propagates through containers the same way normal dropping does.
+
+use core::mem::{forget, ManuallyDrop};
+use core::ptr;
+
+use crate::ValueDrop;
+
+impl<T: ValueDrop> ValueDrop for Option<T> {
+    fn drop(self) {
+        if let Some(val) = self {
+            val.drop();
+        }
+    }
+}
+
+///Drops the `[idx, len)` tail of a `*mut T` element-by-element, by value. Used to finish
+/// dropping the remaining elements of an array if an earlier element's `drop(self)` unwinds,
+/// mirroring how the compiler's own drop glue for slices stays panic-safe. `Vec` uses its own
+/// guard, which also frees the buffer.
+struct RemainderDropGuard<T: ValueDrop> {
+    ptr: *mut T,
+    len: usize,
+    idx: usize,
+}
+
+impl<T: ValueDrop> Drop for RemainderDropGuard<T> {
+    fn drop(&mut self) {
+        while self.idx < self.len {
+            //Safety: every index in `[idx, len)` is a live, not-yet-read element of the
+            //original array, and `idx` is advanced before calling `drop` so a panic here
+            //resumes from the next element instead of re-reading this one.
+            let val = unsafe { ptr::read(self.ptr.add(self.idx)) };
+            self.idx += 1;
+            val.drop();
+        }
+    }
+}
+
+impl<T: ValueDrop, const N: usize> ValueDrop for [T; N] {
+    fn drop(self) {
+        let mut this = ManuallyDrop::new(self);
+        let mut guard = RemainderDropGuard { ptr: this.as_mut_ptr(), len: N, idx: 0 };
+        while guard.idx < guard.len {
+            let val = unsafe { ptr::read(guard.ptr.add(guard.idx)) };
+            guard.idx += 1;
+            val.drop();
+        }
+        forget(guard);
+    }
+}
+
+macro_rules! tuple_impl {
+    ($($name:ident)+) => {
+        impl<$($name: ValueDrop),+> ValueDrop for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn drop(self) {
+                let ($($name,)+) = self;
+                $($name.drop();)+
+            }
+        }
+    };
+}
+
+tuple_impl!(A);
+tuple_impl!(A B);
+tuple_impl!(A B C);
+tuple_impl!(A B C D);
+tuple_impl!(A B C D E);
+tuple_impl!(A B C D E F);
+tuple_impl!(A B C D E F G);
+tuple_impl!(A B C D E F G H);
+
+#[cfg(feature = "alloc")]
+mod alloc_impls {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::mem::ManuallyDrop;
+    use core::ptr;
+
+    use crate::ValueDrop;
+
+    ///Drops the `[idx, len)` tail of a `Vec<T>`'s buffer element-by-element, by value, and always
+    /// frees the buffer itself when this guard drops - whether that's because the loop finished
+    /// normally or because an earlier element's `drop(self)` unwound. This is what
+    /// [super::RemainderDropGuard] cannot do for `Vec`: the dealloc needs the same unconditional
+    /// "runs during unwind too" guarantee as the remaining elements, so both live in one guard.
+    struct VecDropGuard<T: ValueDrop> {
+        ptr: *mut T,
+        cap: usize,
+        len: usize,
+        idx: usize,
+    }
+
+    impl<T: ValueDrop> Drop for VecDropGuard<T> {
+        fn drop(&mut self) {
+            while self.idx < self.len {
+                //Safety: every index in `[idx, len)` is a live, not-yet-read element of the
+                //Vec's buffer, and `idx` is advanced before calling `drop` so a panic here
+                //resumes from the next element instead of re-reading this one.
+                let val = unsafe { ptr::read(self.ptr.add(self.idx)) };
+                self.idx += 1;
+                val.drop();
+            }
+            //All elements have been moved out above; reconstitute the backing allocation as an
+            //empty `Vec<ManuallyDrop<T>>` (same layout as `Vec<T>`) so it's freed without
+            //re-dropping anything. Runs even if one of the `drop` calls above panicked.
+            drop(unsafe { Vec::from_raw_parts(self.ptr as *mut ManuallyDrop<T>, 0, self.cap) });
+        }
+    }
+
+    impl<T: ValueDrop> ValueDrop for Vec<T> {
+        fn drop(self) {
+            let mut this = ManuallyDrop::new(self);
+            let mut guard = VecDropGuard {
+                ptr: this.as_mut_ptr(),
+                cap: this.capacity(),
+                len: this.len(),
+                idx: 0,
+            };
+            while guard.idx < guard.len {
+                //Safety: same as [VecDropGuard::drop], operating directly on the Vec's buffer.
+                let val = unsafe { ptr::read(guard.ptr.add(guard.idx)) };
+                guard.idx += 1;
+                val.drop();
+            }
+            //`guard` drops here unconditionally (idx == len by this point, so its `Drop` impl
+            //just frees the buffer) - and, if `val.drop()` above panicked instead, unwinding
+            //drops `guard` early to finish the remaining elements and still free the buffer.
+        }
+    }
+
+    impl<T: ValueDrop> ValueDrop for Box<T> {
+        fn drop(self) {
+            let ptr = Box::into_raw(self);
+            //Safety: `ptr` is a live, uniquely-owned allocation from `Box::into_raw` above.
+            let val = unsafe { ptr::read(ptr) };
+            //Free the allocation via a same-layout `Box<ManuallyDrop<T>>` so it doesn't also
+            //drop the value we already moved out.
+            drop(unsafe { Box::from_raw(ptr as *mut ManuallyDrop<T>) });
+            val.drop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::sync::Mutex;
+
+    use crate::ValueDrop;
+
+    #[test]
+    fn option_drops_some() {
+        struct DropTest(usize);
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                assert_eq!(self.0, 5, "Option<T> dropped the wrong value");
+            }
+        }
+
+        Some(DropTest(5)).drop();
+        None::<DropTest>.drop();
+    }
+
+    #[test]
+    fn array_drops_in_order() {
+        static ORDER: Mutex<std::vec::Vec<usize>> = Mutex::new(std::vec::Vec::new());
+        struct DropTest(usize);
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                ORDER.lock().unwrap().push(self.0);
+            }
+        }
+
+        [DropTest(0), DropTest(1), DropTest(2)].drop();
+        assert_eq!(*ORDER.lock().unwrap(), &[0, 1, 2], "Array did not drop in declaration order");
+    }
+
+    #[test]
+    fn array_continues_dropping_after_panic() {
+        static ORDER: Mutex<std::vec::Vec<usize>> = Mutex::new(std::vec::Vec::new());
+        struct DropTest(usize);
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                ORDER.lock().unwrap().push(self.0);
+                if self.0 == 1 {
+                    panic!("boom");
+                }
+            }
+        }
+
+        let result = std::panic::catch_unwind(|| {
+            [DropTest(0), DropTest(1), DropTest(2)].drop();
+        });
+        assert!(result.is_err(), "drop should have propagated the panic");
+        assert_eq!(
+            *ORDER.lock().unwrap(),
+            &[0, 1, 2],
+            "Remaining elements were not dropped after a panic"
+        );
+    }
+
+    #[test]
+    fn tuple_drops_in_order() {
+        static ORDER: Mutex<std::vec::Vec<usize>> = Mutex::new(std::vec::Vec::new());
+        struct DropTest(usize);
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                ORDER.lock().unwrap().push(self.0);
+            }
+        }
+
+        (DropTest(0), DropTest(1), DropTest(2)).drop();
+        assert_eq!(*ORDER.lock().unwrap(), &[0, 1, 2], "Tuple did not drop in declaration order");
+    }
+}
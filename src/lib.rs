@@ -1,7 +1,7 @@
 //! This crate is a helper utility for structs that need to drop using self instead of
 //! &mut self as provided by [core::ops::Drop].
 //!
-//! This crate contains 2 things:
+//! This crate contains 4 things:
 //!
 //! 1. The trait [ValueDrop]. Types that need to drop using self should implement this trait.
 //!
@@ -12,6 +12,18 @@
 //! [core::cmp::Eq], [core::cmp::PartialEq], [core::cmp::Ord], [core::cmp::PartialOrd], and
 //! [core::hash::Hash] when possible.
 //!
+//! 3. The struct [ValueGuard] and its [defer_value] constructor. This is the [AutoValueDrop]
+//!    equivalent for a one-off by-value cleanup closure, for when writing a dedicated [ValueDrop]
+//!    impl isn't worth it.
+//!
+//! 4. The trait [TryValueDrop] and the struct [AutoTryValueDrop]. These mirror [ValueDrop] and
+//!    [AutoValueDrop] for cleanup that can fail, with [AutoTryValueDrop::close] offering a way to
+//!    consume the wrapper early and surface the error, which a normal [core::ops::Drop] cannot do.
+//!
+//! [ValueDrop] is also implemented for the common compositions Rust's own drop glue covers -
+//! [Option], arrays, tuples, and (behind the `alloc` feature) `Vec` and `Box` - so an
+//! [AutoValueDrop] can wrap, say, a `Vec` of a `ValueDrop` type directly.
+//!
 //! This crate is no_std by default.
 //!
 //! # Example
@@ -44,10 +56,24 @@
 //! ```
 #![no_std]
 
-use core::mem::{forget, swap, ManuallyDrop};
+#[cfg(feature = "std")]
+extern crate std;
 
-use core::mem::uninitialized;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::mem::{forget, ManuallyDrop};
 use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+mod impls;
+
+///Derives [ValueDrop] for a struct or enum by destructuring `self` and tearing down each field
+/// in declaration order: fields are passed to [ValueDrop::drop] by default,
+/// `#[value_drop(plain)]` fields run their ordinary [core::ops::Drop] glue instead, and
+/// `#[value_drop(skip)]` fields are forgotten. Requires the `derive` feature.
+#[cfg(feature = "derive")]
+pub use selfdrop_derive::ValueDrop;
 
 ///A Drop implementation for types that need to use self instead of &mut self when dropping
 pub trait ValueDrop {
@@ -73,21 +99,42 @@ impl<T: ValueDrop> AutoValueDrop<T> {
     /// As such, if you call this method you should either put it back into an [AutoValueDrop] or
     /// ensure that you manually call drop on it.
     #[inline(always)]
-    pub fn into_inner(mut slot: Self) -> T {
+    pub fn into_inner(slot: Self) -> T {
         //Can't just take because Self implements Drop
-        let mut val = unsafe { uninitialized() };
-        swap(&mut slot.0, &mut val);
-        //Run forget on slot as it now contains uninitialized data
+        //Safety: `slot` is forgotten immediately after, so `slot.0` is never read again and is
+        //never double-dropped.
+        let val = unsafe { ptr::read(&slot.0) };
         forget(slot);
         ManuallyDrop::into_inner(val)
     }
+
+    ///Transforms the wrapped value with `f`, moving the original value out (suppressing its
+    /// automatic drop) and rewrapping the result so it gains automatic drop in turn. This saves
+    /// the error-prone [into_inner](AutoValueDrop::into_inner) -> transform -> [new](
+    /// AutoValueDrop::new) dance, which risks forgetting to re-wrap and leaking or double-
+    /// freeing the underlying resource.
+    #[inline(always)]
+    pub fn map<U: ValueDrop, F: FnOnce(T) -> U>(slot: Self, f: F) -> AutoValueDrop<U> {
+        AutoValueDrop::new(f(Self::into_inner(slot)))
+    }
+
+    ///Fallible counterpart to [map](AutoValueDrop::map): transforms the wrapped value with `f`,
+    /// returning the mapped [AutoValueDrop] on success or `f`'s error otherwise. Note that `f`
+    /// takes `T` by value, so on failure it's `f`'s responsibility to dispose of it.
+    #[inline(always)]
+    pub fn try_map<U: ValueDrop, E, F: FnOnce(T) -> Result<U, E>>(
+        slot: Self,
+        f: F,
+    ) -> Result<AutoValueDrop<U>, E> {
+        Ok(AutoValueDrop::new(f(Self::into_inner(slot))?))
+    }
 }
 
 impl<T: ValueDrop> Drop for AutoValueDrop<T> {
     #[inline(always)]
     fn drop(&mut self) {
-        let mut val = unsafe { uninitialized() };
-        swap(&mut self.0, &mut val);
+        //Safety: `self` is never accessed again after this read, as we're already inside `drop`.
+        let val = unsafe { ptr::read(&self.0) };
         ManuallyDrop::into_inner(val).drop()
     }
 }
@@ -110,9 +157,164 @@ impl<T: ValueDrop> DerefMut for AutoValueDrop<T> {
 unsafe impl<T: ValueDrop + Send> Send for AutoValueDrop<T> {}
 unsafe impl<T: ValueDrop + Sync> Sync for AutoValueDrop<T> {}
 
+///Attaches a by-value cleanup closure `f` to `value`, to be run with ownership of `value` when
+/// the returned [ValueGuard] drops. See [ValueGuard] for details.
+#[inline(always)]
+pub fn defer_value<T, F: FnOnce(T)>(value: T, f: F) -> ValueGuard<T, F> {
+    ValueGuard::new(value, f)
+}
+
+///A wrapper type that will automatically call its closure on its contents, by value, when this
+/// struct is dropped. This is the [defer_value] counterpart of [AutoValueDrop] for the common
+/// case of a one-off cleanup (e.g. an FFI `free(data, argument)` call) that doesn't warrant a
+/// dedicated [ValueDrop] impl.
+pub struct ValueGuard<T, F: FnOnce(T)>(ManuallyDrop<T>, ManuallyDrop<F>);
+
+impl<T, F: FnOnce(T)> ValueGuard<T, F> {
+    ///Constructs a new [ValueGuard], deferring `f(value)` until the guard is dropped.
+    #[inline(always)]
+    pub fn new(value: T, f: F) -> Self {
+        Self(ManuallyDrop::new(value), ManuallyDrop::new(f))
+    }
+
+    ///Get's the [ValueGuard]'s value. Neither the inner data nor the closure will be
+    /// automatically run.
+    #[inline(always)]
+    pub fn into_inner(slot: Self) -> T {
+        //Safety: `slot` is forgotten immediately after, so neither field is read again or
+        //dropped twice.
+        let val = unsafe { ptr::read(&slot.0) };
+        forget(slot);
+        ManuallyDrop::into_inner(val)
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for ValueGuard<T, F> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        //Safety: `self` is never accessed again after these reads, as we're already inside `drop`.
+        let val = unsafe { ptr::read(&self.0) };
+        let f = unsafe { ptr::read(&self.1) };
+        ManuallyDrop::into_inner(f)(ManuallyDrop::into_inner(val))
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for ValueGuard<T, F> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, F: FnOnce(T)> DerefMut for ValueGuard<T, F> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+unsafe impl<T: Send, F: FnOnce(T) + Send> Send for ValueGuard<T, F> {}
+unsafe impl<T: Sync, F: FnOnce(T) + Sync> Sync for ValueGuard<T, F> {}
+
+///A fallible counterpart to [ValueDrop], for resources (sockets, files, ...) whose by-value
+/// teardown can fail. Prefer [ValueDrop] when cleanup cannot fail, since [core::ops::Drop]
+/// (and therefore [AutoTryValueDrop]'s automatic scope-exit drop) has no way to surface an
+/// error to the caller - use [AutoTryValueDrop::close] for that.
+pub trait TryValueDrop {
+    type Error;
+    fn try_drop(self) -> Result<(), Self::Error>;
+}
+
+///What [AutoTryValueDrop] should do with the error if [TryValueDrop::try_drop] fails during a
+/// normal scope-exit drop. Has no effect on [AutoTryValueDrop::close], which always hands the
+/// error back to the caller.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum OnDropError {
+    ///Silently discard the error.
+    Ignore,
+    ///Panic with a message noting that `try_drop` failed.
+    Panic,
+    ///Abort the process. Requires the `std` feature.
+    #[cfg(feature = "std")]
+    Abort,
+}
+
+///A wrapper type that will automatically call [TryValueDrop::try_drop] on it's contents when
+/// this struct is dropped by normal Rusty means, per its [OnDropError] policy. Use [close](
+/// AutoTryValueDrop::close) instead of a normal drop to consume the wrapper early and get the
+/// `try_drop` error back.
+pub struct AutoTryValueDrop<T: TryValueDrop>(ManuallyDrop<T>, OnDropError);
+
+impl<T: TryValueDrop> AutoTryValueDrop<T> {
+    ///Constructs a new [AutoTryValueDrop], silently discarding the error if `try_drop` fails
+    /// during a normal scope-exit drop. Use [new_with](AutoTryValueDrop::new_with) to choose a
+    /// different [OnDropError] policy.
+    #[inline(always)]
+    pub fn new(val: T) -> Self {
+        Self::new_with(val, OnDropError::Ignore)
+    }
+
+    ///Constructs a new [AutoTryValueDrop] with an explicit [OnDropError] policy for what happens
+    /// if `try_drop` fails during a normal scope-exit drop.
+    #[inline(always)]
+    pub fn new_with(val: T, on_error: OnDropError) -> Self {
+        Self(ManuallyDrop::new(val), on_error)
+    }
+
+    ///Consumes the wrapper early, running `try_drop` and handing the error back to the caller
+    /// instead of applying the [OnDropError] policy. This defuses the automatic drop so
+    /// `try_drop` runs exactly once.
+    #[inline(always)]
+    pub fn close(slot: Self) -> Result<(), T::Error> {
+        //Safety: `slot` is forgotten immediately after, so `slot.0` is never read again and is
+        //never double-dropped.
+        let val = unsafe { ptr::read(&slot.0) };
+        forget(slot);
+        ManuallyDrop::into_inner(val).try_drop()
+    }
+}
+
+impl<T: TryValueDrop> Drop for AutoTryValueDrop<T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        //Safety: `self` is never accessed again after this read, as we're already inside `drop`.
+        let val = unsafe { ptr::read(&self.0) };
+        if ManuallyDrop::into_inner(val).try_drop().is_err() {
+            match self.1 {
+                OnDropError::Ignore => {}
+                OnDropError::Panic => panic!("AutoTryValueDrop::drop: try_drop failed"),
+                #[cfg(feature = "std")]
+                OnDropError::Abort => std::process::abort(),
+            }
+        }
+    }
+}
+
+impl<T: TryValueDrop> Deref for AutoTryValueDrop<T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: TryValueDrop> DerefMut for AutoTryValueDrop<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+unsafe impl<T: TryValueDrop + Send> Send for AutoTryValueDrop<T> {}
+unsafe impl<T: TryValueDrop + Sync> Sync for AutoTryValueDrop<T> {}
+
 #[cfg(test)]
 mod tests {
-    use crate::{AutoValueDrop, ValueDrop};
+    use crate::{
+        defer_value, AutoTryValueDrop, AutoValueDrop, OnDropError, TryValueDrop, ValueDrop,
+        ValueGuard,
+    };
 
     #[test]
     fn basic_drop() {
@@ -221,4 +423,111 @@ mod tests {
         assert_eq!(unsafe { LAST_DROP }, 5, "Dropped in wrong order");
     }
 
+    #[test]
+    fn guard_runs_closure_with_value() {
+        const X_VAL: usize = 5;
+
+        let a = defer_value(X_VAL, |x| assert_eq!(x, X_VAL, "Guard closure saw wrong value"));
+        drop(a);
+    }
+
+    #[test]
+    fn guard_into_inner_skips_closure() {
+        const X_VAL: usize = 5;
+
+        let a = defer_value(X_VAL, |_| panic!("This closure should NOT be called"));
+        let y = ValueGuard::into_inner(a);
+        assert_eq!(y, X_VAL, "Value was not what was expected");
+    }
+
+    #[test]
+    fn close_surfaces_error() {
+        struct FailTryDrop;
+
+        impl TryValueDrop for FailTryDrop {
+            type Error = &'static str;
+            fn try_drop(self) -> Result<(), Self::Error> {
+                Err("failed to close")
+            }
+        }
+
+        let a = AutoTryValueDrop::new(FailTryDrop);
+        assert_eq!(AutoTryValueDrop::close(a), Err("failed to close"));
+    }
+
+    #[test]
+    fn ignored_drop_error_does_not_panic() {
+        struct FailTryDrop;
+
+        impl TryValueDrop for FailTryDrop {
+            type Error = &'static str;
+            fn try_drop(self) -> Result<(), Self::Error> {
+                Err("failed to close")
+            }
+        }
+
+        let a = AutoTryValueDrop::new_with(FailTryDrop, OnDropError::Ignore);
+        drop(a);
+    }
+
+    #[test]
+    #[should_panic(expected = "try_drop failed")]
+    fn panicking_drop_error_panics() {
+        struct FailTryDrop;
+
+        impl TryValueDrop for FailTryDrop {
+            type Error = &'static str;
+            fn try_drop(self) -> Result<(), Self::Error> {
+                Err("failed to close")
+            }
+        }
+
+        let a = AutoTryValueDrop::new_with(FailTryDrop, OnDropError::Panic);
+        drop(a);
+    }
+
+    #[test]
+    fn map_transforms_and_rewraps() {
+        struct DropTest(usize);
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                panic!("This drop should NOT be called");
+            }
+        }
+
+        let a = AutoValueDrop::new(DropTest(5));
+        let b = AutoValueDrop::map(a, |x| DropTest(x.0 * 2));
+        let c = AutoValueDrop::into_inner(b);
+        assert_eq!(c.0, 10, "map did not transform the wrapped value");
+    }
+
+    #[test]
+    fn try_map_propagates_error() {
+        struct DropTest {
+            _x: usize,
+        }
+        impl ValueDrop for DropTest {
+            fn drop(self) {
+                panic!("This drop should NOT be called");
+            }
+        }
+
+        let a = AutoValueDrop::new(DropTest { _x: 5 });
+        let result: Result<AutoValueDrop<DropTest>, &'static str> =
+            AutoValueDrop::try_map(a, |_| Err("transform failed"));
+        assert_eq!(result.err(), Some("transform failed"));
+    }
+
+    #[test]
+    #[cfg(feature = "derive")]
+    fn derive_resolves_path_from_inside_this_crate() {
+        #[derive(ValueDrop)]
+        struct DropTest {
+            #[value_drop(plain)]
+            _x: usize,
+        }
+
+        DropTest { _x: 5 }.drop();
+    }
+
 }
@@ -0,0 +1,72 @@
+//! Exercises `#[derive(ValueDrop)]` the way a downstream crate would: depending on `selfdrop`
+//! through its published name rather than `crate`. Requires the `derive` feature.
+#![cfg(feature = "derive")]
+
+use std::sync::Mutex;
+
+use selfdrop::{ValueDrop, ValueGuard};
+
+static ORDER: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+///Held for the whole of each test, since every test clears and then inspects the shared `ORDER`.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Logs(&'static str);
+
+impl ValueDrop for Logs {
+    fn drop(self) {
+        ORDER.lock().unwrap().push(self.0);
+    }
+}
+
+#[derive(ValueDrop)]
+struct Connection {
+    primary: Logs,
+    secondary: Logs,
+    #[value_drop(plain)]
+    label: String,
+    #[value_drop(skip)]
+    leaked: ValueGuard<(), fn(())>,
+}
+
+#[test]
+fn derived_impl_drops_fields_in_order_and_honors_attributes() {
+    let _serial = SERIAL.lock().unwrap();
+    ORDER.lock().unwrap().clear();
+
+    let conn = Connection {
+        primary: Logs("primary"),
+        secondary: Logs("secondary"),
+        label: String::from("conn-1"),
+        leaked: ValueGuard::new((), |_| panic!("skipped field must not run its closure")),
+    };
+    ValueDrop::drop(conn);
+
+    assert_eq!(
+        *ORDER.lock().unwrap(),
+        &["primary", "secondary"],
+        "derived drop did not tear down ValueDrop fields in declaration order"
+    );
+}
+
+///A generic struct whose own `impl` block never bounds `T: ValueDrop` - the derive should add
+/// that bound itself, the same way `#[derive(Clone)]` et al. infer bounds for their users.
+#[derive(ValueDrop)]
+struct Wrapper<T> {
+    inner: T,
+}
+
+#[test]
+fn derived_impl_infers_value_drop_bound_for_generic_field() {
+    let _serial = SERIAL.lock().unwrap();
+    ORDER.lock().unwrap().clear();
+
+    let wrapper = Wrapper { inner: Logs("inner") };
+    ValueDrop::drop(wrapper);
+
+    assert_eq!(
+        *ORDER.lock().unwrap(),
+        &["inner"],
+        "derived impl did not drop the generic field"
+    );
+}